@@ -1,3 +1,5 @@
+use clap::Parser;
+use futures::future::join_all;
 use html5ever::{
   buffer_queue::BufferQueue,
   tendril::Tendril,
@@ -5,23 +7,65 @@ use html5ever::{
 };
 use itertools::Itertools;
 use regex::Regex;
-use serde_json::json;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 const BASE_URL: &str = "https://www.who.int";
 const PATH: &str = "/emergencies/diseases/novel-coronavirus-2019/situation-reports/";
+const CACHE_DIR: &str = "cache/situation-reports";
+const MAX_CONCURRENT_FETCHES: usize = 8;
+
+/// Fetches, parses, and queries WHO COVID-19 situation reports.
+#[derive(Parser)]
+struct Cli {
+  /// Serialize as Elasticsearch `_bulk` NDJSON targeting this index, instead of JSON
+  #[arg(long)]
+  bulk_index: Option<String>,
+  /// POST the bulk NDJSON straight to this Elasticsearch base URL
+  #[arg(long, requires = "bulk_index")]
+  es_url: Option<String>,
+  /// Render an aligned terminal table and bar chart of the latest report, instead of JSON
+  #[arg(long)]
+  table: bool,
+  /// Print only the N highest-ranked countries from the latest report, by --by;
+  /// with --table, the full table is still printed and N sets the number of
+  /// bar-chart rows (default 10)
+  #[arg(long)]
+  top: Option<usize>,
+  /// Metric to rank or chart countries by (suspected-case counts are only
+  /// ever populated for China; every other country reports null/0)
+  #[arg(long, value_enum, default_value_t = Metric::Confirmed)]
+  by: Metric,
+  /// Print only this country's record from the latest report
+  #[arg(long)]
+  country: Option<String>,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Metric {
+  Confirmed,
+  Deaths,
+  Suspected,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-  let latest_url = {
+  let cli = Cli::parse();
+
+  let report_urls: Vec<String> = {
     let index_url = format!("{}{}", BASE_URL, PATH);
     let body = reqwest::get(&index_url)
       .await?
       .bytes()
       .await?;
 
-    let sink = LatestSituationReportFinder::default();
+    let sink = SituationReportFinder::default();
     let mut tokenizer = Tokenizer::new(sink, Default::default());
     let mut queue = BufferQueue::new();
     let tendril = Tendril::try_from_byte_slice(&body)
@@ -29,169 +73,534 @@ async fn main() -> Result<()> {
     queue.push_back(tendril);
     let _ = tokenizer.feed(&mut queue);
     tokenizer.end();
-    tokenizer.sink.url
+    tokenizer.sink.urls.into_iter().unique().collect()
   };
 
-  if let Some(url) = latest_url {
-    let body = reqwest::get(&url)
-      .await?
-      .bytes()
-      .await?;
-    let document = lopdf::Document::load_mem(&body)?;
-    let page_numbers: Vec<u32> = document.get_pages().keys().copied().collect();
-    let cases_re = Regex::new(r#"(?x)
-      ^\s*\d+(\s+\(\s*\d+\s*\))?\s*$
-    "#)?;
-    let text = document.extract_text(&page_numbers)?;
-    let mut all_regions_iter = text
-        .lines()
-        .filter_map(|line| match line.trim() {
-          "" => None,
-          line => Some(line.to_string()),
-        })
-        .skip_while(|line| line != "Hubei")
-        .take_while(|line| line != "Case classifications are")
-        .coalesce(|prev, cur| if cur.starts_with("(") && cases_re.is_match(&prev) && !prev.contains('(') {
-          Ok(format!("{} {}", prev, cur))
+  if report_urls.is_empty() {
+    panic!("no situation report URLs found in HTML document");
+  }
+
+  std::fs::create_dir_all(CACHE_DIR)?;
+
+  let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_FETCHES));
+  let fetches = report_urls.into_iter().map(|url| {
+    let semaphore = Arc::clone(&semaphore);
+    tokio::spawn(async move {
+      let _permit = semaphore.acquire().await?;
+      fetch_report(&url).await
+    })
+  });
+
+  let mut time_series: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+  for joined in join_all(fetches).await {
+    match joined {
+      Ok(Ok((date, body))) => match parse_report(&body) {
+        Ok(countries) => { time_series.insert(date, countries); },
+        Err(err) => eprintln!("skipping {}: failed to parse report: {}", date, err),
+      },
+      Ok(Err(err)) => eprintln!("skipping report: {}", err),
+      Err(err) => eprintln!("skipping report: task failed: {}", err),
+    }
+  }
+  compute_deltas(&mut time_series);
+
+  if let Some(country) = &cli.country {
+    let (_date, countries) = time_series.iter().next_back()
+      .ok_or("no reports parsed")?;
+    match find_country(countries, country) {
+      Some(record) => println!("{}", serde_json::to_string_pretty(record)?),
+      None => eprintln!("country not found in latest report: {}", country),
+    }
+  }
+  else if let Some(index) = &cli.bulk_index {
+    let ndjson = bulk_ndjson(&time_series, index);
+    match &cli.es_url {
+      Some(es_url) => index_bulk(es_url, index, &ndjson).await?,
+      None => print!("{}", ndjson),
+    }
+  }
+  else if cli.table {
+    let (_date, countries) = time_series.iter().next_back()
+      .ok_or("no reports parsed")?;
+    print!("{}", render_table(countries));
+    println!();
+    print!("{}", render_bar_chart(countries, cli.by, cli.top.unwrap_or(10), 40));
+  }
+  else if let Some(top) = cli.top {
+    let (_date, countries) = time_series.iter().next_back()
+      .ok_or("no reports parsed")?;
+    println!("{}", serde_json::to_string_pretty(&top_n(countries, cli.by, top))?);
+  }
+  else {
+    println!("{}", serde_json::to_string_pretty(&time_series)?);
+  }
+
+  Ok(())
+}
+
+fn render_table(countries: &[Value]) -> String {
+  let mut rows = countries.to_vec();
+  rows.sort_by_key(|country| std::cmp::Reverse(metric_value(country, Metric::Confirmed)));
+
+  let name_width = rows.iter()
+    .filter_map(|country| country.get("name").and_then(Value::as_str))
+    .map(str::len)
+    .max()
+    .unwrap_or(0)
+    .max("Country".len());
+
+  let mut table = format!(
+    "{:<name_width$}  {:>10}  {:>10}  {:>10}\n",
+    "Country", "Confirmed", "Deaths", "Suspected",
+    name_width = name_width,
+  );
+  for country in &rows {
+    let name = country.get("name").and_then(Value::as_str).unwrap_or("?");
+    table.push_str(&format!(
+      "{:<name_width$}  {:>10}  {:>10}  {:>10}\n",
+      name,
+      metric_value(country, Metric::Confirmed),
+      metric_value(country, Metric::Deaths),
+      metric_value(country, Metric::Suspected),
+      name_width = name_width,
+    ));
+  }
+  table
+}
+
+fn render_bar_chart(countries: &[Value], metric: Metric, n: usize, width: usize) -> String {
+  let rows = top_n(countries, metric, n);
+
+  let max_value = rows.iter()
+    .map(|country| metric_value(country, metric))
+    .max()
+    .unwrap_or(0);
+  let name_width = rows.iter()
+    .filter_map(|country| country.get("name").and_then(Value::as_str))
+    .map(str::len)
+    .max()
+    .unwrap_or(0);
+
+  let mut chart = String::new();
+  for country in &rows {
+    let name = country.get("name").and_then(Value::as_str).unwrap_or("?");
+    let value = metric_value(country, metric);
+    let bar_len = if max_value == 0 {
+      0
+    }
+    else {
+      (value as f64 / max_value as f64 * width as f64).round() as usize
+    };
+    chart.push_str(&format!(
+      "{:<name_width$}  {}  {}\n",
+      name,
+      "█".repeat(bar_len),
+      value,
+      name_width = name_width,
+    ));
+  }
+  chart
+}
+
+fn top_n(countries: &[Value], metric: Metric, n: usize) -> Vec<Value> {
+  let mut rows = countries.to_vec();
+  rows.sort_by_key(|country| std::cmp::Reverse(metric_value(country, metric)));
+  rows.truncate(n);
+  rows
+}
+
+fn find_country<'a>(countries: &'a [Value], query: &str) -> Option<&'a Value> {
+  let target = normalize_for_matching(query);
+  countries.iter()
+    .find(|country| country.get("name").and_then(Value::as_str)
+      .map(normalize_for_matching)
+      .as_deref()
+      == Some(target.as_str()))
+}
+
+fn metric_value(country: &Value, metric: Metric) -> u64 {
+  let field = match metric {
+    Metric::Deaths => "total_deaths",
+    Metric::Confirmed => "total_confirmed_cases",
+    Metric::Suspected => "today_suspected_cases",
+  };
+  country.get(field).and_then(Value::as_u64).unwrap_or(0)
+}
+
+fn bulk_ndjson(time_series: &BTreeMap<String, Vec<Value>>, index: &str) -> String {
+  let mut ndjson = String::new();
+  for (date, countries) in time_series {
+    for country in countries {
+      bulk_doc(&mut ndjson, index, date, country);
+      if let Some(regions) = country.get("regions").and_then(Value::as_array) {
+        for region in regions {
+          bulk_doc(&mut ndjson, index, date, region);
+        }
+      }
+    }
+  }
+  ndjson
+}
+
+fn bulk_doc(ndjson: &mut String, index: &str, date: &str, doc: &Value) {
+  let name = doc.get("name").and_then(Value::as_str).unwrap_or("unknown");
+  let metadata = json!({
+    "index": {
+      "_index": index,
+      "_id": format!("{}-{}", name, date)
+    }
+  });
+  let mut doc = doc.clone();
+  if let Value::Object(ref mut fields) = doc {
+    fields.insert("@timestamp".to_string(), json!(format!("{}T00:00:00Z", date)));
+    fields.insert("geo".to_string(), match country_geo(name) {
+      Some((lat, lon)) => json!({ "lat": lat, "lon": lon }),
+      None => Value::Null,
+    });
+  }
+  ndjson.push_str(&metadata.to_string());
+  ndjson.push('\n');
+  ndjson.push_str(&doc.to_string());
+  ndjson.push('\n');
+}
+
+async fn index_bulk(es_url: &str, index: &str, ndjson: &str) -> Result<()> {
+  let bulk_url = format!("{}/{}/_bulk", es_url, index);
+  let client = reqwest::Client::new();
+  let response = client.post(&bulk_url)
+    .header("Content-Type", "application/x-ndjson")
+    .body(ndjson.to_string())
+    .send()
+    .await?;
+  let status = response.status();
+  let body: Value = response.json().await?;
+  if !status.is_success() {
+    return Err(format!("Elasticsearch bulk index failed: {} {}", status, body).into());
+  }
+  if body.get("errors").and_then(Value::as_bool).unwrap_or(false) {
+    let failed: Vec<&Value> = body.get("items")
+      .and_then(Value::as_array)
+      .into_iter()
+      .flatten()
+      .filter(|item| item.get("index").and_then(|index| index.get("error")).is_some())
+      .collect();
+    return Err(format!("Elasticsearch bulk index reported item errors: {:?}", failed).into());
+  }
+  Ok(())
+}
+
+fn country_geo(name: &str) -> Option<(f64, f64)> {
+  match name {
+    "China" => Some((35.8617, 104.1954)),
+    "Japan" => Some((36.2048, 138.2529)),
+    "Republic of Korea" => Some((35.9078, 127.7669)),
+    "Singapore" => Some((1.3521, 103.8198)),
+    "Thailand" => Some((15.8700, 100.9925)),
+    "Viet Nam" => Some((14.0583, 108.2772)),
+    "Australia" => Some((-25.2744, 133.7751)),
+    "United States of America" => Some((37.0902, -95.7129)),
+    "United Kingdom" => Some((55.3781, -3.4360)),
+    "France" => Some((46.2276, 2.2137)),
+    "Germany" => Some((51.1657, 10.4515)),
+    "Italy" => Some((41.8719, 12.5674)),
+    "Spain" => Some((40.4637, -3.7492)),
+    "Iran (Islamic Republic of)" => Some((32.4279, 53.6880)),
+    "Finland" => Some((61.9241, 25.7482)),
+    _ => None,
+  }
+}
+
+async fn fetch_report(url: &str) -> Result<(String, Vec<u8>)> {
+  let filename = url
+    .rsplit('/')
+    .next()
+    .ok_or_else(|| format!("couldn't derive filename from {:?}", url))?;
+  let date = report_date(filename)
+    .ok_or_else(|| format!("couldn't derive report date from {:?}", filename))?;
+  let cache_path = PathBuf::from(CACHE_DIR).join(filename);
+
+  if cache_path.exists() {
+    return Ok((date, std::fs::read(&cache_path)?));
+  }
+
+  let body = reqwest::get(url).await?.bytes().await?;
+  std::fs::write(&cache_path, &body)?;
+  Ok((date, body.to_vec()))
+}
+
+fn report_date(filename: &str) -> Option<String> {
+  let date_re = Regex::new(r"^(\d{4})(\d{2})(\d{2})").ok()?;
+  let captures = date_re.captures(filename)?;
+  Some(format!("{}-{}-{}", &captures[1], &captures[2], &captures[3]))
+}
+
+fn compute_deltas(time_series: &mut BTreeMap<String, Vec<Value>>) {
+  let mut country_totals: HashMap<String, (u64, u64)> = HashMap::new();
+  let mut region_totals: HashMap<String, (u64, u64)> = HashMap::new();
+  for countries in time_series.values_mut() {
+    for country in countries.iter_mut() {
+      if let Some(Value::Array(regions)) = country.get_mut("regions") {
+        for region in regions.iter_mut() {
+          apply_delta(region, &mut region_totals);
+        }
+      }
+      apply_delta(country, &mut country_totals);
+    }
+  }
+}
+
+fn apply_delta(doc: &mut Value, prev_totals: &mut HashMap<String, (u64, u64)>) {
+  let name = match doc.get("name").and_then(Value::as_str) {
+    Some(name) => name.to_string(),
+    None => return,
+  };
+  let confirmed = doc.get("total_confirmed_cases").and_then(Value::as_u64).unwrap_or(0);
+  let deaths = doc.get("total_deaths").and_then(Value::as_u64).unwrap_or(0);
+  let deltas = prev_totals.get(&name)
+    .map(|&(prev_confirmed, prev_deaths)| (
+      confirmed.saturating_sub(prev_confirmed),
+      deaths.saturating_sub(prev_deaths),
+    ));
+
+  if let Value::Object(fields) = doc {
+    let (new_confirmed, new_deaths) = match deltas {
+      Some((new_confirmed, new_deaths)) => (json!(new_confirmed), json!(new_deaths)),
+      None => (Value::Null, Value::Null),
+    };
+    fields.insert("new_confirmed".to_string(), new_confirmed);
+    fields.insert("new_deaths".to_string(), new_deaths);
+  }
+
+  prev_totals.insert(name, (confirmed, deaths));
+}
+
+fn parse_report(body: &[u8]) -> Result<Vec<Value>> {
+  let document = lopdf::Document::load_mem(body)?;
+  let page_numbers: Vec<u32> = document.get_pages().keys().copied().collect();
+  let cases_re = Regex::new(r#"(?x)
+    ^\s*\d+(\s+\(\s*\d+\s*\))?\s*$
+  "#)?;
+  let text = document.extract_text(&page_numbers)?;
+  let mut all_regions_iter = text
+      .lines()
+      .filter_map(|line| match line.trim() {
+        "" => None,
+        line => Some(line.to_string()),
+      })
+      .skip_while(|line| line != "Hubei")
+      .take_while(|line| line != "Case classifications are")
+      .coalesce(|prev, cur| if cur.starts_with("(") && cases_re.is_match(&prev) && !prev.contains('(') {
+        Ok(format!("{} {}", prev, cur))
+      }
+      else {
+        Err((prev, cur))
+      })
+      .batching(|it| {
+        let mut preamble: Vec<_> = it
+          .take_while_ref(|line| !cases_re.is_match(line))
+          .filter(|line| !(
+            line.contains("Region")
+            || line.contains(" - ")
+            || line.contains("Unimplemented?")
+          ))
+          .collect();
+        let mut preamble = if preamble.iter().any(|el| el == "Country/Territory/Area") {
+          preamble.pop().unwrap()
         }
         else {
-          Err((prev, cur))
-        })
-        .batching(|it| {
-          let mut preamble: Vec<_> = it
-            .take_while_ref(|line| !cases_re.is_match(line))
-            .filter(|line| !(
-              line.contains("Region")
-              || line.contains(" - ")
-              || line.contains("Unimplemented?")
-            ))
-            .collect();
-          let mut preamble = if preamble.iter().any(|el| el == "Country/Territory/Area") {
-            preamble.pop().unwrap()
-          }
-          else {
-            preamble.join(" ")
-          };
-          if preamble.starts_with(")") {
-            preamble = preamble.chars().skip(1).collect();
-          }
-          if preamble.ends_with("ยง") {
-            let count = preamble.chars().count();
-            preamble = preamble.chars().take(count - 1).collect();
-          }
-          preamble = preamble
-            .trim()
-            .replace("Total", "China")
-            .replace("Uni ted", "United")
-            .replace("Finlan d", "Finland")
-            .replace("Jian gsu", "Jiangsu")
-            .replace("South - ", "");
-          let counts: Vec<_> = it
-            .take_while_ref(|line| cases_re.is_match(line))
-            .map(|count| {
-              if count.contains("(") {
-                count.split(|c| c == '(' || c == ')')
-                  .take(2)
-                  .map(|c|
-                    c.trim()
-                      .parse::<u32>()
-                      .map_err(|_| format!("failed to parse: {:?}", c))
-                      .unwrap()
-                  )
-                  .collect()
-              }
-              else {
-                vec![
-                  count.parse::<u32>()
-                    .map_err(|_| format!("failed to parse: {:?}", count))
+          preamble.join(" ")
+        };
+        if preamble.starts_with(")") {
+          preamble = preamble.chars().skip(1).collect();
+        }
+        if preamble.ends_with("ยง") {
+          let count = preamble.chars().count();
+          preamble = preamble.chars().take(count - 1).collect();
+        }
+        preamble = preamble
+          .trim()
+          .replace("Total", "China")
+          .replace("Jian gsu", "Jiangsu")
+          .replace("South - ", "");
+        let counts: Vec<_> = it
+          .take_while_ref(|line| cases_re.is_match(line))
+          .map(|count| {
+            if count.contains("(") {
+              count.split(|c| c == '(' || c == ')')
+                .take(2)
+                .map(|c|
+                  c.trim()
+                    .parse::<u32>()
+                    .map_err(|_| format!("failed to parse: {:?}", c))
                     .unwrap()
-                ]
-              }
-            })
-            .collect();
-          if preamble.is_empty() || counts.is_empty() {
-            None
-          }
-          else {
-            Some((preamble, counts))
-          }
-        })
-        .filter(|(preamble, counts)|
-          counts.len() >= 6
-          && preamble != "Subtotal for all regions"
-          && preamble != "Grand total"
-        );
-    let china_regions: Vec<_> = all_regions_iter
-      .take_while_ref(|(region, _counts)| region != "China")
-      .map(|(region_name, counts)| {
-        json!({
-          "name": region_name,
-          "population": counts[0][0],
-          "today_confirmed_cases": counts[1][0],
-          "today_suspected_cases": counts[2][0],
-          "today_deaths": counts[3][0],
-          "total_confirmed_cases": counts[4][0],
-          "total_deaths": counts[5][0]
-        })
-      })
-      .collect();
-    let countries: Vec<_> = all_regions_iter
-      .map(|(country_name, counts)| {
-        if country_name == "China" {
-          json!({
-            "name": "China",
-            "today_confirmed_cases": counts[1][0],
-            "today_suspected_cases": counts[2][0],
-            "total_confirmed_cases": counts[4][0],
-            "today_likely_place_of_exposure_china": null,
-            "total_likely_place_of_exposure_china": null,
-            "today_likely_place_of_exposure_in_country": null,
-            "total_likely_place_of_exposure_in_country": null,
-            "today_likely_place_of_exposure_other": null,
-            "total_likely_place_of_exposure_other": null,
-            "today_likely_place_of_exposure_unknown": null,
-            "total_likely_place_of_exposure_unknown": null,
-            "today_deaths": counts[3][0],
-            "total_deaths": counts[5][0],
-            "regions": &china_regions
+                )
+                .collect()
+            }
+            else {
+              vec![
+                count.parse::<u32>()
+                  .map_err(|_| format!("failed to parse: {:?}", count))
+                  .unwrap()
+              ]
+            }
           })
+          .collect();
+        if preamble.is_empty() || counts.is_empty() {
+          None
         }
         else {
-          json!({
-            "name": country_name,
-            "today_confirmed_cases": counts[0][1],
-            "today_suspected_cases": null,
-            "total_confirmed_cases": counts[0][0],
-            "today_likely_place_of_exposure_china": counts[1][1],
-            "total_likely_place_of_exposure_china": counts[1][0],
-            "today_likely_place_of_exposure_in_country": counts[3][1],
-            "total_likely_place_of_exposure_in_country": counts[3][0],
-            "today_likely_place_of_exposure_other": counts[2][1],
-            "total_likely_place_of_exposure_other": counts[2][0],
-            "today_likely_place_of_exposure_unknown": counts[4][1],
-            "total_likely_place_of_exposure_unknown": counts[4][0],
-            "today_deaths": counts[5][1],
-            "total_deaths": counts[5][0],
-            "regions": null
-          })
+          Some((preamble, counts))
         }
       })
-      .collect();
-    println!("{}", serde_json::to_string_pretty(&countries)?);
-  }
-  else {
-    panic!("URL for PDF not found in HTML document");
-  }
+      .filter(|(preamble, counts)|
+        counts.len() >= 6
+        && preamble != "Subtotal for all regions"
+        && preamble != "Grand total"
+      );
+  let china_regions: Vec<_> = all_regions_iter
+    .take_while_ref(|(region, _counts)| region != "China")
+    .map(|(region_name, counts)| {
+      json!({
+        "name": region_name,
+        "iso2": null,
+        "iso3": null,
+        "population": counts[0][0],
+        "today_confirmed_cases": counts[1][0],
+        "today_suspected_cases": counts[2][0],
+        "today_deaths": counts[3][0],
+        "total_confirmed_cases": counts[4][0],
+        "total_deaths": counts[5][0]
+      })
+    })
+    .collect();
+  let countries: Vec<_> = all_regions_iter
+    .map(|(country_name, counts)| {
+      let iso = iso_3166_lookup(&country_name);
+      let name = iso.map(|(canonical, _iso2, _iso3)| canonical.to_string()).unwrap_or(country_name.clone());
+      let iso2 = iso.map(|(_canonical, iso2, _iso3)| iso2);
+      let iso3 = iso.map(|(_canonical, _iso2, iso3)| iso3);
+      if country_name == "China" {
+        json!({
+          "name": name,
+          "iso2": iso2,
+          "iso3": iso3,
+          "today_confirmed_cases": counts[1][0],
+          "today_suspected_cases": counts[2][0],
+          "total_confirmed_cases": counts[4][0],
+          "today_likely_place_of_exposure_china": null,
+          "total_likely_place_of_exposure_china": null,
+          "today_likely_place_of_exposure_in_country": null,
+          "total_likely_place_of_exposure_in_country": null,
+          "today_likely_place_of_exposure_other": null,
+          "total_likely_place_of_exposure_other": null,
+          "today_likely_place_of_exposure_unknown": null,
+          "total_likely_place_of_exposure_unknown": null,
+          "today_deaths": counts[3][0],
+          "total_deaths": counts[5][0],
+          "regions": &china_regions
+        })
+      }
+      else {
+        json!({
+          "name": name,
+          "iso2": iso2,
+          "iso3": iso3,
+          "today_confirmed_cases": counts[0][1],
+          "today_suspected_cases": null,
+          "total_confirmed_cases": counts[0][0],
+          "today_likely_place_of_exposure_china": counts[1][1],
+          "total_likely_place_of_exposure_china": counts[1][0],
+          "today_likely_place_of_exposure_in_country": counts[3][1],
+          "total_likely_place_of_exposure_in_country": counts[3][0],
+          "today_likely_place_of_exposure_other": counts[2][1],
+          "total_likely_place_of_exposure_other": counts[2][0],
+          "today_likely_place_of_exposure_unknown": counts[4][1],
+          "total_likely_place_of_exposure_unknown": counts[4][0],
+          "today_deaths": counts[5][1],
+          "total_deaths": counts[5][0],
+          "regions": null
+        })
+      }
+    })
+    .collect();
+  Ok(countries)
+}
 
-  Ok(())
+const ISO_3166_TABLE: &[(&str, &str, &str)] = &[
+  ("China", "CN", "CHN"),
+  ("Japan", "JP", "JPN"),
+  ("Republic of Korea", "KR", "KOR"),
+  ("Singapore", "SG", "SGP"),
+  ("Thailand", "TH", "THA"),
+  ("Viet Nam", "VN", "VNM"),
+  ("Malaysia", "MY", "MYS"),
+  ("Philippines", "PH", "PHL"),
+  ("Indonesia", "ID", "IDN"),
+  ("Cambodia", "KH", "KHM"),
+  ("Nepal", "NP", "NPL"),
+  ("Sri Lanka", "LK", "LKA"),
+  ("India", "IN", "IND"),
+  ("Australia", "AU", "AUS"),
+  ("New Zealand", "NZ", "NZL"),
+  ("United States of America", "US", "USA"),
+  ("Canada", "CA", "CAN"),
+  ("United Kingdom", "GB", "GBR"),
+  ("France", "FR", "FRA"),
+  ("Germany", "DE", "DEU"),
+  ("Italy", "IT", "ITA"),
+  ("Spain", "ES", "ESP"),
+  ("Finland", "FI", "FIN"),
+  ("Sweden", "SE", "SWE"),
+  ("Russian Federation", "RU", "RUS"),
+  ("United Arab Emirates", "AE", "ARE"),
+  ("Iran (Islamic Republic of)", "IR", "IRN"),
+  ("Iraq", "IQ", "IRQ"),
+  ("Egypt", "EG", "EGY"),
+  ("Lebanon", "LB", "LBN"),
+  ("Israel", "IL", "ISR"),
+  ("Brazil", "BR", "BRA"),
+  ("Mexico", "MX", "MEX"),
+  ("Belgium", "BE", "BEL"),
+  ("Afghanistan", "AF", "AFG"),
+  ("Pakistan", "PK", "PAK"),
+];
+
+fn iso_3166_lookup(name: &str) -> Option<(&'static str, &'static str, &'static str)> {
+  let target = normalize_for_matching(name);
+  ISO_3166_TABLE.iter()
+    .find(|(canonical, _iso2, _iso3)| normalize_for_matching(canonical) == target)
+    .map(|&(canonical, iso2, iso3)| (canonical, iso2, iso3))
+}
+
+fn normalize_for_matching(name: &str) -> String {
+  strip_diacritics(name)
+    .chars()
+    .filter(|c| !c.is_whitespace())
+    .collect::<String>()
+    .to_lowercase()
+}
+
+fn strip_diacritics(name: &str) -> String {
+  name.chars()
+    .map(|c| match c {
+      'à' | 'á' | 'â' | 'ã' | 'ä' => 'a',
+      'è' | 'é' | 'ê' | 'ë' => 'e',
+      'ì' | 'í' | 'î' | 'ï' => 'i',
+      'ò' | 'ó' | 'ô' | 'õ' | 'ö' => 'o',
+      'ù' | 'ú' | 'û' | 'ü' => 'u',
+      'ç' => 'c',
+      'ñ' => 'n',
+      other => other,
+    })
+    .collect()
 }
 
 #[derive(Default)]
-struct LatestSituationReportFinder {
-  url: Option<String>,
+struct SituationReportFinder {
+  urls: Vec<String>,
 }
 
-impl TokenSink for LatestSituationReportFinder {
+impl TokenSink for SituationReportFinder {
   type Handle = ();
 
   fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
@@ -202,8 +611,7 @@ impl TokenSink for LatestSituationReportFinder {
       => {
         if let Some(href) = tag.attrs.iter().find(|a| &a.name.local == "href") {
           if href.value.starts_with("/docs/default-source/coronaviruse/situation-reports/") {
-            self.url = Some(format!("{}{}", BASE_URL, href.value));
-            return TokenSinkResult::Plaintext;
+            self.urls.push(format!("{}{}", BASE_URL, href.value));
           }
         }
       },
@@ -212,3 +620,88 @@ impl TokenSink for LatestSituationReportFinder {
     TokenSinkResult::Continue
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn normalize_for_matching_merges_inserted_whitespace() {
+    assert_eq!(normalize_for_matching("Uni ted Kingdom"), normalize_for_matching("United Kingdom"));
+    assert_eq!(normalize_for_matching("Jian gsu"), normalize_for_matching("Jiangsu"));
+  }
+
+  #[test]
+  fn normalize_for_matching_strips_diacritics_and_case() {
+    assert_eq!(normalize_for_matching("Côte d'Ivoire"), normalize_for_matching("cote D'ivoire"));
+  }
+
+  #[test]
+  fn iso_3166_lookup_matches_across_inserted_whitespace() {
+    let (canonical, iso2, iso3) = iso_3166_lookup("Uni ted Kingdom").unwrap();
+    assert_eq!(canonical, "United Kingdom");
+    assert_eq!(iso2, "GB");
+    assert_eq!(iso3, "GBR");
+  }
+
+  #[test]
+  fn iso_3166_lookup_returns_none_for_unknown_country() {
+    assert!(iso_3166_lookup("Narnia").is_none());
+  }
+
+  #[test]
+  fn apply_delta_computes_difference_from_previous_total() {
+    let mut totals = HashMap::new();
+    let mut first = json!({ "name": "China", "total_confirmed_cases": 100, "total_deaths": 5 });
+    apply_delta(&mut first, &mut totals);
+    assert_eq!(first["new_confirmed"], Value::Null);
+    assert_eq!(first["new_deaths"], Value::Null);
+
+    let mut second = json!({ "name": "China", "total_confirmed_cases": 150, "total_deaths": 8 });
+    apply_delta(&mut second, &mut totals);
+    assert_eq!(second["new_confirmed"], json!(50));
+    assert_eq!(second["new_deaths"], json!(3));
+  }
+
+  #[test]
+  fn apply_delta_clamps_at_zero_on_downward_revision() {
+    let mut totals = HashMap::new();
+    let mut first = json!({ "name": "China", "total_confirmed_cases": 100, "total_deaths": 10 });
+    apply_delta(&mut first, &mut totals);
+
+    let mut revised = json!({ "name": "China", "total_confirmed_cases": 90, "total_deaths": 10 });
+    apply_delta(&mut revised, &mut totals);
+    assert_eq!(revised["new_confirmed"], json!(0));
+    assert_eq!(revised["new_deaths"], json!(0));
+  }
+
+  #[test]
+  fn compute_deltas_tracks_countries_and_regions_independently() {
+    let mut time_series: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    time_series.insert("2020-01-01".to_string(), vec![json!({
+      "name": "China",
+      "total_confirmed_cases": 100,
+      "total_deaths": 5,
+      "regions": [
+        { "name": "Hubei", "total_confirmed_cases": 80, "total_deaths": 4 }
+      ]
+    })]);
+    time_series.insert("2020-01-02".to_string(), vec![json!({
+      "name": "China",
+      "total_confirmed_cases": 120,
+      "total_deaths": 6,
+      "regions": [
+        { "name": "Hubei", "total_confirmed_cases": 95, "total_deaths": 5 }
+      ]
+    })]);
+
+    compute_deltas(&mut time_series);
+
+    let second_day = &time_series["2020-01-02"][0];
+    assert_eq!(second_day["new_confirmed"], json!(20));
+    assert_eq!(second_day["new_deaths"], json!(1));
+    let hubei = &second_day["regions"][0];
+    assert_eq!(hubei["new_confirmed"], json!(15));
+    assert_eq!(hubei["new_deaths"], json!(1));
+  }
+}